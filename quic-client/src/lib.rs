@@ -10,12 +10,13 @@ use {
     crate::{
         nonblocking::quic_client::{
             QuicClient, QuicClientConnection as NonblockingQuicClientConnection,
-            QuicLazyInitializedEndpoint,
+            QuicLazyInitializedEndpoint, QuicTransportConfig,
         },
         quic_client::{
             close_quic_connection, QuicClientConnection as BlockingQuicClientConnection,
         },
     },
+    futures::future::join_all,
     log::debug,
     quic_client::get_runtime,
     quinn::{Endpoint, EndpointConfig, TokioRuntime},
@@ -33,13 +34,46 @@ use {
     solana_tls_utils::{new_dummy_x509_certificate, QuicClientCertificate},
     std::{
         net::{IpAddr, SocketAddr, UdpSocket},
-        sync::{Arc, RwLock},
+        sync::{Arc, Mutex, RwLock, Weak},
     },
 };
 
+/// Idle timeout for the turbine/broadcast profile: shorter than the TPU default so a
+/// dead fanout peer is evicted instead of holding an endpoint open for a full slot.
+pub const QUIC_TURBINE_MAX_TIMEOUT_MS: u64 = 1_000;
+pub const QUIC_TURBINE_KEEP_ALIVE_MS: u64 = 300;
+
+/// Turbine/broadcast's per-peer stream budget is much smaller than TPU forwarding's: a
+/// broadcast sender fans out to many more peers at once, so each one gets a thinner slice.
+pub const QUIC_TURBINE_MIN_STAKED_STREAMS: usize = 32;
+pub const QUIC_TURBINE_MAX_STAKED_CONCURRENT_STREAMS: usize = 512;
+pub const QUIC_TURBINE_MAX_UNSTAKED_CONCURRENT_STREAMS: usize = 16;
+
+/// Pure stake-ratio math behind [`QuicConfig::compute_max_concurrent_streams`], split out
+/// so it can be unit tested without needing a real `StakedNodes` map.
+fn stake_weighted_stream_limit(
+    stake: u64,
+    total_stake: u64,
+    min_staked_streams: usize,
+    max_staked_concurrent_streams: usize,
+    max_unstaked_concurrent_streams: usize,
+) -> usize {
+    if stake == 0 || total_stake == 0 {
+        return max_unstaked_concurrent_streams;
+    }
+    let allowed = (stake as f64 / total_stake as f64 * max_staked_concurrent_streams as f64)
+        as usize;
+    allowed.max(min_staked_streams)
+}
+
 pub struct QuicPool {
     connections: Vec<Arc<Quic>>,
     endpoint: Arc<QuicLazyInitializedEndpoint>,
+    // Every `QuicClient` this pool hands out is also registered here (weakly) so that
+    // `QuicConnectionManager::update_key` can proactively close them when our identity
+    // rotates, rather than leaving them to handshake with the stale certificate until
+    // they happen to be recreated.
+    tracked_connections: Arc<Mutex<Vec<Weak<QuicClient>>>>,
 }
 impl ConnectionPool for QuicPool {
     type BaseClientConnection = Quic;
@@ -57,21 +91,38 @@ impl ConnectionPool for QuicPool {
     }
 
     fn get(&self, index: usize) -> Result<Arc<Self::BaseClientConnection>, ConnectionPoolError> {
-        self.connections
+        let preferred = self
+            .connections
             .get(index)
-            .cloned()
-            .ok_or(ConnectionPoolError::IndexOutOfRange)
+            .ok_or(ConnectionPoolError::IndexOutOfRange)?;
+        if preferred.0.is_healthy() {
+            return Ok(preferred.clone());
+        }
+        // `preferred` is cooling down after repeated failures; prefer any sibling that's
+        // currently healthy instead of hammering a connection we expect to fail again.
+        Ok(self
+            .connections
+            .iter()
+            .find(|connection| connection.0.is_healthy())
+            .unwrap_or(preferred)
+            .clone())
     }
 
     fn create_pool_entry(
         &self,
-        _config: &Self::NewConnectionConfig,
+        config: &Self::NewConnectionConfig,
         addr: &SocketAddr,
     ) -> Arc<Self::BaseClientConnection> {
-        Arc::new(Quic(Arc::new(QuicClient::new(
+        let client = Arc::new(QuicClient::new(
             self.endpoint.clone(),
             *addr,
-        ))))
+            config.compute_max_concurrent_streams(),
+        ));
+        self.tracked_connections
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&client));
+        Arc::new(Quic(client))
     }
 }
 
@@ -97,6 +148,11 @@ pub struct QuicConfig {
     // The optional specified endpoint for the quic based client connections
     // If not specified, the connection cache will create as needed.
     client_endpoint: Option<Endpoint>,
+
+    // Transport knobs that distinguish one profile (e.g. turbine) from another (e.g.
+    // TPU forwarding). A profile either carries its own bound `client_endpoint` or, when
+    // none is set, each of its pools creates its own endpoint lazily.
+    transport_config: QuicTransportConfig,
 }
 
 impl Clone for QuicConfig {
@@ -107,6 +163,7 @@ impl Clone for QuicConfig {
             maybe_staked_nodes: self.maybe_staked_nodes.clone(),
             maybe_client_pubkey: self.maybe_client_pubkey,
             client_endpoint: self.client_endpoint.clone(),
+            transport_config: self.transport_config,
         }
     }
 }
@@ -122,14 +179,47 @@ impl NewConnectionConfig for QuicConfig {
             maybe_staked_nodes: None,
             maybe_client_pubkey: None,
             client_endpoint: None,
+            transport_config: QuicTransportConfig::default(),
         })
     }
 }
 
 impl QuicConfig {
+    /// Tuned for TPU transaction forwarding: a longer idle timeout amortizes the
+    /// handshake cost across a whole leader slot's worth of sends.
+    pub fn new_tpu_profile() -> Result<Self, ClientError> {
+        Self::new()
+    }
+
+    /// Tuned for low-latency, high-fanout turbine/broadcast connections: a short idle
+    /// timeout evicts dead peers quickly instead of holding a socket open for a slot.
+    pub fn new_turbine_profile() -> Result<Self, ClientError> {
+        let mut config = Self::new()?;
+        config.transport_config = QuicTransportConfig {
+            max_idle_timeout_ms: QUIC_TURBINE_MAX_TIMEOUT_MS,
+            keep_alive_interval_ms: QUIC_TURBINE_KEEP_ALIVE_MS,
+            min_staked_streams: QUIC_TURBINE_MIN_STAKED_STREAMS,
+            max_staked_concurrent_streams: QUIC_TURBINE_MAX_STAKED_CONCURRENT_STREAMS,
+            max_unstaked_concurrent_streams: QUIC_TURBINE_MAX_UNSTAKED_CONCURRENT_STREAMS,
+        };
+        Ok(config)
+    }
+
+    pub fn set_max_idle_timeout_ms(&mut self, max_idle_timeout_ms: u64) {
+        self.transport_config.max_idle_timeout_ms = max_idle_timeout_ms;
+    }
+
+    pub fn set_keep_alive_interval_ms(&mut self, keep_alive_interval_ms: u64) {
+        self.transport_config.keep_alive_interval_ms = keep_alive_interval_ms;
+    }
+
     fn create_endpoint(&self) -> QuicLazyInitializedEndpoint {
         let cert_guard = self.client_certificate.read().unwrap();
-        QuicLazyInitializedEndpoint::new(cert_guard.clone(), self.client_endpoint.as_ref().cloned())
+        QuicLazyInitializedEndpoint::new(
+            cert_guard.clone(),
+            self.client_endpoint.as_ref().cloned(),
+            self.transport_config,
+        )
     }
 
     pub fn update_client_certificate(&mut self, keypair: &Keypair, _ipaddr: IpAddr) {
@@ -163,6 +253,32 @@ impl QuicConfig {
         self.maybe_client_pubkey = Some(*client_pubkey);
     }
 
+    /// How many concurrent unidirectional streams we're allowed to have in flight to a
+    /// single peer, based on our stake relative to the rest of the cluster. Mirrors the
+    /// server-side `compute_max_allowed_uni_streams(ConnectionPeerType, total_stake)` QoS
+    /// calculation in the streamer so we don't race it into resetting our streams.
+    fn compute_max_concurrent_streams(&self) -> usize {
+        let Some(client_pubkey) = self.maybe_client_pubkey else {
+            return self.transport_config.max_unstaked_concurrent_streams;
+        };
+        let Some(staked_nodes) = self.maybe_staked_nodes.as_ref() else {
+            return self.transport_config.max_unstaked_concurrent_streams;
+        };
+        let staked_nodes = staked_nodes.read().unwrap();
+        let stake = staked_nodes
+            .staked_nodes
+            .get(&client_pubkey)
+            .copied()
+            .unwrap_or(0);
+        stake_weighted_stream_limit(
+            stake,
+            staked_nodes.total_stake,
+            self.transport_config.min_staked_streams,
+            self.transport_config.max_staked_concurrent_streams,
+            self.transport_config.max_unstaked_concurrent_streams,
+        )
+    }
+
     pub fn update_client_endpoint(&mut self, client_socket: UdpSocket) {
         let runtime = get_runtime();
         let _guard = runtime.enter();
@@ -175,6 +291,15 @@ impl QuicConfig {
 }
 
 pub struct Quic(Arc<QuicClient>);
+
+impl Quic {
+    /// Tears down this pool entry's connection and forces a fresh handshake on the next
+    /// send, rather than continuing to reuse one that's been repeatedly failing.
+    pub fn reconnect(&self) {
+        close_quic_connection(self.0.clone());
+    }
+}
+
 impl BaseClientConnection for Quic {
     type BlockingClientConnection = BlockingQuicClientConnection;
     type NonblockingClientConnection = NonblockingQuicClientConnection;
@@ -204,6 +329,9 @@ impl BaseClientConnection for Quic {
 
 pub struct QuicConnectionManager {
     connection_config: QuicConfig,
+    // Weak handles to every `QuicClient` ever handed out by a pool this manager created,
+    // so identity rotation can reach into already-established connections.
+    tracked_connections: Arc<Mutex<Vec<Weak<QuicClient>>>>,
 }
 
 impl ConnectionManager for QuicConnectionManager {
@@ -216,6 +344,7 @@ impl ConnectionManager for QuicConnectionManager {
         QuicPool {
             connections: Vec::default(),
             endpoint: Arc::new(self.connection_config.create_endpoint()),
+            tracked_connections: self.tracked_connections.clone(),
         }
     }
 
@@ -225,13 +354,65 @@ impl ConnectionManager for QuicConnectionManager {
 
     fn update_key(&self, key: &Keypair) -> Result<(), Box<dyn std::error::Error>> {
         self.connection_config.update_keypair(key);
+        self.invalidate_tracked_connections();
+        self.update_stake_weighted_limits();
         Ok(())
     }
 }
 
 impl QuicConnectionManager {
     pub fn new_with_connection_config(connection_config: QuicConfig) -> Self {
-        Self { connection_config }
+        Self {
+            connection_config,
+            tracked_connections: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Re-keys every live connection's endpoint to the new identity and closes its
+    /// cached `Connection`, so the next handshake both presents the new certificate and
+    /// is forced to actually happen (rather than reusing a connection already
+    /// established under the old one). Re-keying the endpoint is what actually fixes
+    /// identity rotation: closing the `Connection` alone would just have it reconnect
+    /// through the same endpoint, which keeps presenting the stale certificate forever.
+    fn invalidate_tracked_connections(&self) {
+        let client_certificate = self
+            .connection_config
+            .client_certificate
+            .read()
+            .unwrap()
+            .clone();
+        let mut tracked = self.tracked_connections.lock().unwrap();
+        tracked.retain(|weak_client| {
+            let Some(client) = weak_client.upgrade() else {
+                return false;
+            };
+            client.rekey_endpoint(client_certificate.clone());
+            close_quic_connection(client);
+            true
+        });
+    }
+
+    /// Re-sizes every live connection's stream-concurrency budget from the current stake
+    /// map, so a stake share that shifted at an epoch boundary is reflected on already
+    /// pooled connections instead of only on newly created ones. Called automatically
+    /// from `update_key` (identity rotation implies a new stake lookup too), since that's
+    /// the one stake-affecting event this crate can observe on its own.
+    ///
+    /// `QuicConfig` only holds a shared reference to the stake map (`set_staked_nodes`
+    /// just points at it, it isn't notified when the map's contents change), so this
+    /// crate has no way to detect an in-place stake update by itself. Whatever owns the
+    /// `Arc<RwLock<StakedNodes>>` and mutates it at an epoch boundary is responsible for
+    /// also calling this method on its `QuicConnectionManager` at that point.
+    pub fn update_stake_weighted_limits(&self) {
+        let allowed = self.connection_config.compute_max_concurrent_streams();
+        let mut tracked = self.tracked_connections.lock().unwrap();
+        tracked.retain(|weak_client| {
+            let Some(client) = weak_client.upgrade() else {
+                return false;
+            };
+            client.set_max_concurrent_streams(allowed);
+            true
+        });
     }
 }
 
@@ -244,9 +425,95 @@ pub fn new_quic_connection_cache(
     staked_nodes: &Arc<RwLock<StakedNodes>>,
     connection_pool_size: usize,
 ) -> Result<QuicConnectionCache, ClientError> {
-    let mut config = QuicConfig::new()?;
+    let mut config = QuicConfig::new_tpu_profile()?;
+    config.update_client_certificate(keypair, ipaddr);
+    config.set_staked_nodes(staked_nodes, &keypair.pubkey());
+    let connection_manager = QuicConnectionManager::new_with_connection_config(config);
+    ConnectionCache::new(name, connection_manager, connection_pool_size)
+}
+
+/// Like [`new_quic_connection_cache`], but tuned for turbine/broadcast fanout instead of
+/// TPU transaction forwarding, so the two can run side by side from the same identity
+/// keypair without contending on the same endpoint or stream budget.
+pub fn new_turbine_quic_connection_cache(
+    name: &'static str,
+    keypair: &Keypair,
+    ipaddr: IpAddr,
+    staked_nodes: &Arc<RwLock<StakedNodes>>,
+    connection_pool_size: usize,
+) -> Result<QuicConnectionCache, ClientError> {
+    let mut config = QuicConfig::new_turbine_profile()?;
     config.update_client_certificate(keypair, ipaddr);
     config.set_staked_nodes(staked_nodes, &keypair.pubkey());
     let connection_manager = QuicConnectionManager::new_with_connection_config(config);
     ConnectionCache::new(name, connection_manager, connection_pool_size)
 }
+
+/// Lets callers (e.g. a leader-tracking service) pre-warm QUIC connections to upcoming
+/// leaders ahead of time, so that when a transaction actually needs forwarding the
+/// handshake is already done.
+#[async_trait::async_trait]
+pub trait WarmQuicConnectionCache {
+    /// Establishes, or reuses, connections to every address in `addrs`, up to the pool
+    /// size the cache was configured with. Idempotent: addresses with an already-warm,
+    /// healthy connection are left untouched.
+    async fn warm_connections(&self, addrs: &[SocketAddr]);
+}
+
+#[async_trait::async_trait]
+impl WarmQuicConnectionCache for QuicConnectionCache {
+    async fn warm_connections(&self, addrs: &[SocketAddr]) {
+        // Fan out concurrently rather than one address at a time: a slow or unreachable
+        // address shouldn't serialize behind the rest of a realistic lookahead list.
+        join_all(addrs.iter().map(|addr| async move {
+            let connection = self.get_nonblocking_connection(addr);
+            if let Err(err) = connection.warm().await {
+                debug!("Failed to pre-warm QUIC connection to {addr}: {err}");
+            }
+        }))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN_STAKED_STREAMS: usize = 128;
+    const MAX_STAKED_CONCURRENT_STREAMS: usize = 2048;
+    const MAX_UNSTAKED_CONCURRENT_STREAMS: usize = 64;
+
+    fn limit(stake: u64, total_stake: u64) -> usize {
+        stake_weighted_stream_limit(
+            stake,
+            total_stake,
+            MIN_STAKED_STREAMS,
+            MAX_STAKED_CONCURRENT_STREAMS,
+            MAX_UNSTAKED_CONCURRENT_STREAMS,
+        )
+    }
+
+    #[test]
+    fn unstaked_or_zero_total_stake_gets_unstaked_limit() {
+        assert_eq!(limit(0, 1_000), MAX_UNSTAKED_CONCURRENT_STREAMS);
+        assert_eq!(limit(100, 0), MAX_UNSTAKED_CONCURRENT_STREAMS);
+    }
+
+    #[test]
+    fn small_stake_is_floored_at_minimum() {
+        assert_eq!(limit(1, 1_000_000), MIN_STAKED_STREAMS);
+    }
+
+    #[test]
+    fn full_stake_gets_max_streams() {
+        assert_eq!(limit(1_000, 1_000), MAX_STAKED_CONCURRENT_STREAMS);
+    }
+
+    #[test]
+    fn stake_share_scales_linearly() {
+        // A quarter of the stake should get a quarter of the staked budget, since that's
+        // comfortably above the floor.
+        let expected = MAX_STAKED_CONCURRENT_STREAMS / 4;
+        assert_eq!(limit(250, 1_000), expected);
+    }
+}
@@ -0,0 +1 @@
+pub mod quic_client;
@@ -0,0 +1,590 @@
+//! Simple nonblocking client that connects to a given UDP port with the QUIC protocol
+//! and provides an interface for sending transactions which is restricted by the
+//! server's flow control.
+use {
+    async_trait::async_trait,
+    log::*,
+    quinn::{
+        ClientConfig, Connection, ConnectionError, Endpoint, EndpointConfig, IdleTimeout,
+        TokioRuntime, TransportConfig,
+    },
+    solana_connection_cache::{
+        client_connection::ClientStats,
+        connection_cache_stats::{ConnectionCacheStats, CONNECTION_STAT_SUBMISSION_INTERVAL},
+        nonblocking::client_connection::ClientConnection,
+    },
+    solana_measure::measure::Measure,
+    solana_tls_utils::{tls_client_config_builder, QuicClientCertificate},
+    std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+        sync::{
+            atomic::{AtomicU32, AtomicU64, Ordering},
+            Arc, RwLock,
+        },
+        time::{Duration, Instant},
+    },
+    thiserror::Error,
+    tokio::sync::{Mutex, Semaphore},
+};
+
+pub const QUIC_MAX_TIMEOUT_MS: u64 = 2_000;
+pub const QUIC_KEEP_ALIVE_MS: u64 = 1_000;
+pub const QUIC_CONNECTION_HANDSHAKE_TIMEOUT_MS: u64 = 2_000;
+
+/// Default (TPU-profile) minimum number of concurrent uni-streams granted to a staked
+/// client, regardless of how small its stake is relative to the rest of the cluster.
+pub const MIN_STAKED_STREAMS: usize = 128;
+/// Default (TPU-profile) upper bound on concurrent uni-streams granted to the
+/// highest-staked client.
+pub const MAX_STAKED_CONCURRENT_STREAMS: usize = 2048;
+/// Default (TPU-profile) concurrent uni-streams granted to a client with no stake (or an
+/// unrecognized pubkey).
+pub const MAX_UNSTAKED_CONCURRENT_STREAMS: usize = 64;
+
+/// Starting cooldown applied after a single failure, doubled for each consecutive
+/// failure thereafter (capped at `MAX_BACKOFF`).
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+/// Upper bound on the backoff cooldown, no matter how many consecutive failures
+/// a connection has racked up.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Tracks consecutive send/handshake failures (and the last success) for a `QuicClient`
+/// so the pool can avoid repeatedly selecting a connection that's unlikely to succeed.
+#[derive(Default)]
+struct ConnectionHealth {
+    consecutive_failures: AtomicU32,
+    last_failure: std::sync::Mutex<Option<Instant>>,
+    last_success: std::sync::Mutex<Option<Instant>>,
+}
+
+/// The backoff cooldown after `failures` consecutive failures: `BASE_BACKOFF` after the
+/// first, doubling each failure thereafter, capped at `MAX_BACKOFF`.
+fn backoff_for(failures: u32) -> Duration {
+    debug_assert!(failures > 0);
+    BASE_BACKOFF
+        .saturating_mul(1u32 << (failures - 1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+impl ConnectionHealth {
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.last_success.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn record_failure(&self) {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_failure.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// A connection is healthy if it has never failed, or if its exponential backoff
+    /// cooldown (see `backoff_for`) has elapsed since its last failure.
+    fn is_healthy(&self) -> bool {
+        let failures = self.consecutive_failures.load(Ordering::Relaxed);
+        if failures == 0 {
+            return true;
+        }
+        match *self.last_failure.lock().unwrap() {
+            Some(last_failure) => last_failure.elapsed() >= backoff_for(failures),
+            None => true,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum QuicError {
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+    #[error(transparent)]
+    ConnectError(#[from] quinn::ConnectError),
+    #[error(transparent)]
+    WriteError(#[from] quinn::WriteError),
+    #[error("connection is cooling down after repeated failures")]
+    Backoff,
+}
+
+/// The transport knobs that differ between QUIC profiles (e.g. a turbine/broadcast
+/// profile wants a short idle timeout to evict dead peers quickly and a smaller
+/// per-peer stream budget to match its high-fanout traffic shape, while a TPU
+/// transaction-forwarding profile wants to amortize handshakes across a whole slot and
+/// grant a much larger budget to the few peers it talks to).
+#[derive(Clone, Copy, Debug)]
+pub struct QuicTransportConfig {
+    pub max_idle_timeout_ms: u64,
+    pub keep_alive_interval_ms: u64,
+    /// Minimum number of concurrent uni-streams granted to a staked client, regardless of
+    /// how small its stake is relative to the rest of the cluster.
+    pub min_staked_streams: usize,
+    /// Upper bound on concurrent uni-streams granted to the highest-staked client.
+    pub max_staked_concurrent_streams: usize,
+    /// Concurrent uni-streams granted to a client with no stake (or an unrecognized pubkey).
+    pub max_unstaked_concurrent_streams: usize,
+}
+
+impl Default for QuicTransportConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_timeout_ms: QUIC_MAX_TIMEOUT_MS,
+            keep_alive_interval_ms: QUIC_KEEP_ALIVE_MS,
+            min_staked_streams: MIN_STAKED_STREAMS,
+            max_staked_concurrent_streams: MAX_STAKED_CONCURRENT_STREAMS,
+            max_unstaked_concurrent_streams: MAX_UNSTAKED_CONCURRENT_STREAMS,
+        }
+    }
+}
+
+pub struct QuicLazyInitializedEndpoint {
+    // Re-keyable in place (see `rekey`) so an identity rotation can install a fresh TLS
+    // client config on the already-created `quinn::Endpoint` below, instead of every
+    // connection it hands out continuing to present the stale certificate forever.
+    client_certificate: RwLock<Arc<QuicClientCertificate>>,
+    endpoint: RwLock<Option<Arc<Endpoint>>>,
+    transport_config: QuicTransportConfig,
+}
+
+impl QuicLazyInitializedEndpoint {
+    pub fn new(
+        client_certificate: Arc<QuicClientCertificate>,
+        client_endpoint: Option<Endpoint>,
+        transport_config: QuicTransportConfig,
+    ) -> Self {
+        Self {
+            client_certificate: RwLock::new(client_certificate),
+            endpoint: RwLock::new(client_endpoint.map(Arc::new)),
+            transport_config,
+        }
+    }
+
+    fn build_client_config(&self, client_certificate: &QuicClientCertificate) -> ClientConfig {
+        let client_config = tls_client_config_builder()
+            .with_client_auth_cert(
+                vec![client_certificate.certificate.clone()],
+                client_certificate.key.clone_key(),
+            )
+            .expect("Failed to set QUIC client certificates");
+
+        let mut transport_config = TransportConfig::default();
+        let timeout = IdleTimeout::try_from(std::time::Duration::from_millis(
+            self.transport_config.max_idle_timeout_ms,
+        ))
+        .unwrap();
+        transport_config.max_idle_timeout(Some(timeout));
+        transport_config.keep_alive_interval(Some(std::time::Duration::from_millis(
+            self.transport_config.keep_alive_interval_ms,
+        )));
+
+        let mut config = ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(client_config)
+                .expect("Failed to create QUIC client config"),
+        ));
+        config.transport_config(Arc::new(transport_config));
+        config
+    }
+
+    fn create_endpoint(&self) -> Endpoint {
+        let client_certificate = self.client_certificate.read().unwrap().clone();
+        let config = self.build_client_config(&client_certificate);
+
+        let socket = UdpSocket::bind((IpAddr::from(Ipv4Addr::UNSPECIFIED), 0))
+            .expect("QuicLazyInitializedEndpoint::create_endpoint bind");
+        let mut endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            socket,
+            Arc::new(TokioRuntime),
+        )
+        .expect("QuicLazyInitializedEndpoint::create_endpoint quinn::Endpoint::new");
+        endpoint.set_default_client_config(config);
+        endpoint
+    }
+
+    /// Installs a fresh TLS client config built from `client_certificate` on the
+    /// already-created `quinn::Endpoint` (if one has been lazily created yet), so the
+    /// next handshake this endpoint performs presents the new identity instead of the
+    /// one it was originally created with. If no endpoint has been created yet, only the
+    /// stored certificate is replaced, and the next `get_endpoint` call builds the endpoint
+    /// with it.
+    pub fn rekey(&self, client_certificate: Arc<QuicClientCertificate>) {
+        let config = self.build_client_config(&client_certificate);
+        *self.client_certificate.write().unwrap() = client_certificate;
+        if let Some(endpoint) = self.endpoint.read().unwrap().as_ref() {
+            endpoint.set_default_client_config(config);
+        }
+    }
+
+    async fn get_endpoint(&self) -> Arc<Endpoint> {
+        let endpoint = self.endpoint.read().unwrap().clone();
+        match endpoint {
+            Some(endpoint) => endpoint,
+            None => {
+                let endpoint = Arc::new(self.create_endpoint());
+                *self.endpoint.write().unwrap() = Some(endpoint.clone());
+                endpoint
+            }
+        }
+    }
+}
+
+/// Aggregated quinn transport telemetry for one destination address, sampled off of
+/// `Connection::stats()` after each send and periodically flushed through
+/// `solana_metrics` so operators can tell whether poor transaction landing is caused by
+/// RTT, loss, or stream exhaustion on a specific leader connection.
+#[derive(Default)]
+struct TransportMetrics {
+    rtt_us: AtomicU64,
+    cwnd: AtomicU64,
+    sent_packets: AtomicU64,
+    lost_packets: AtomicU64,
+    lost_bytes: AtomicU64,
+    congestion_events: AtomicU64,
+    uni_streams_opened: AtomicU64,
+}
+
+impl TransportMetrics {
+    fn sample(&self, connection: &Connection) {
+        let stats = connection.stats();
+        self.rtt_us
+            .store(stats.path.rtt.as_micros() as u64, Ordering::Relaxed);
+        self.cwnd.store(stats.path.cwnd, Ordering::Relaxed);
+        self.sent_packets
+            .store(stats.path.sent_packets, Ordering::Relaxed);
+        self.lost_packets
+            .store(stats.path.lost_packets, Ordering::Relaxed);
+        self.lost_bytes.store(stats.path.lost_bytes, Ordering::Relaxed);
+        self.congestion_events
+            .store(stats.path.congestion_events, Ordering::Relaxed);
+    }
+
+    fn report(&self, addr: &SocketAddr) {
+        datapoint_info!(
+            "quic_client_transport",
+            ("server_addr", addr.to_string(), String),
+            ("rtt_us", self.rtt_us.load(Ordering::Relaxed), i64),
+            ("cwnd", self.cwnd.load(Ordering::Relaxed), i64),
+            ("sent_packets", self.sent_packets.load(Ordering::Relaxed), i64),
+            ("lost_packets", self.lost_packets.load(Ordering::Relaxed), i64),
+            ("lost_bytes", self.lost_bytes.load(Ordering::Relaxed), i64),
+            (
+                "congestion_events",
+                self.congestion_events.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "uni_streams_opened",
+                self.uni_streams_opened.load(Ordering::Relaxed),
+                i64
+            ),
+        );
+    }
+}
+
+/// A lazily-initialized connection to a single remote address, shared across
+/// every [`QuicClientConnection`] (blocking or nonblocking) created for that address.
+pub struct QuicClient {
+    endpoint: Arc<QuicLazyInitializedEndpoint>,
+    addr: SocketAddr,
+    connection: Arc<Mutex<Option<Connection>>>,
+    // Bounds the number of unidirectional streams we'll have outstanding to this peer at
+    // once, so we stay within whatever a stake-weighted QoS server will actually admit for
+    // us instead of getting streams reset. Sized by `QuicConfig::compute_max_concurrent_streams`
+    // and re-sized in place by `set_max_concurrent_streams` whenever our stake share changes,
+    // rather than being fixed for the lifetime of the pooled connection.
+    stream_concurrency: RwLock<Arc<Semaphore>>,
+    health: ConnectionHealth,
+    transport_metrics: TransportMetrics,
+}
+
+impl QuicClient {
+    pub fn new(
+        endpoint: Arc<QuicLazyInitializedEndpoint>,
+        addr: SocketAddr,
+        max_concurrent_uni_streams: usize,
+    ) -> Self {
+        Self {
+            endpoint,
+            addr,
+            connection: Arc::new(Mutex::new(None)),
+            stream_concurrency: RwLock::new(Arc::new(Semaphore::new(max_concurrent_uni_streams))),
+            health: ConnectionHealth::default(),
+            transport_metrics: TransportMetrics::default(),
+        }
+    }
+
+    pub fn server_addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    /// Re-sizes the uni-stream concurrency budget in place, e.g. after
+    /// `QuicConfig::compute_max_concurrent_streams` produces a new value because our
+    /// stake share shifted at an epoch boundary. Permits already acquired against the
+    /// old budget are unaffected; only subsequent acquires observe the new size.
+    pub fn set_max_concurrent_streams(&self, max_concurrent_uni_streams: usize) {
+        *self.stream_concurrency.write().unwrap() = Arc::new(Semaphore::new(max_concurrent_uni_streams));
+    }
+
+    /// Re-keys this client's endpoint so its next handshake presents `client_certificate`
+    /// instead of whatever identity the endpoint was created with. See
+    /// [`QuicLazyInitializedEndpoint::rekey`].
+    pub(crate) fn rekey_endpoint(&self, client_certificate: Arc<QuicClientCertificate>) {
+        self.endpoint.rekey(client_certificate);
+    }
+
+    /// Whether this connection's recent send/handshake history suggests it's currently
+    /// worth preferring over a sibling in the pool (see `ConnectionHealth::is_healthy`).
+    pub fn is_healthy(&self) -> bool {
+        self.health.is_healthy()
+    }
+
+    /// Tears down a broken connection and forces a fresh handshake on the next send,
+    /// rather than continuing to reuse a connection that has been repeatedly failing.
+    pub async fn reconnect(&self) {
+        self.invalidate_connection().await;
+    }
+
+    /// Proactively establishes (or reuses) the underlying QUIC connection without sending
+    /// any data, so that whichever send comes first doesn't have to pay handshake latency.
+    pub async fn warm(&self) -> Result<(), QuicError> {
+        self.make_connection().await?;
+        Ok(())
+    }
+
+    async fn connect(&self) -> Result<Connection, QuicError> {
+        let endpoint = self.endpoint.get_endpoint().await;
+        let connecting = endpoint.connect(self.addr, "connect")?;
+        Ok(connecting.await?)
+    }
+
+    async fn make_connection(&self) -> Result<Connection, QuicError> {
+        let mut conn_guard = self.connection.lock().await;
+        if let Some(conn) = conn_guard.as_ref() {
+            if conn.close_reason().is_none() {
+                return Ok(conn.clone());
+            }
+        }
+        // No live connection to reuse, so this would be a fresh handshake attempt:
+        // apply the exponential backoff instead of hammering an address that's been
+        // repeatedly failing.
+        if !self.health.is_healthy() {
+            return Err(QuicError::Backoff);
+        }
+        let conn = self.connect().await?;
+        *conn_guard = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Forces the next send to re-establish the QUIC handshake instead of reusing
+    /// whatever connection is currently cached.
+    pub async fn invalidate_connection(&self) {
+        let mut conn_guard = self.connection.lock().await;
+        if let Some(conn) = conn_guard.take() {
+            conn.close(0u32.into(), b"invalidated");
+        }
+    }
+
+    /// Tracks a newly opened uni-stream and, every `CONNECTION_STAT_SUBMISSION_INTERVAL`
+    /// streams, flushes the latest sampled transport stats for this peer.
+    fn note_uni_stream_opened(&self) {
+        let opened = self
+            .transport_metrics
+            .uni_streams_opened
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if opened % CONNECTION_STAT_SUBMISSION_INTERVAL == 0 {
+            self.transport_metrics.report(&self.addr);
+        }
+    }
+
+    async fn _send_buffer(&self, data: &[u8], stats: &ClientStats) -> Result<(), QuicError> {
+        // Backpressure against our own stream budget rather than flooding the peer and
+        // racing its QoS stage into resetting the stream.
+        let semaphore = self.stream_concurrency.read().unwrap().clone();
+        let _permit = semaphore.acquire_owned().await.unwrap();
+        let result = async {
+            let connection = self.make_connection().await?;
+            let mut send_stream = connection.open_uni().await?;
+            self.note_uni_stream_opened();
+            send_stream.write_all(data).await?;
+            send_stream.finish()?;
+            self.transport_metrics.sample(&connection);
+            Ok::<(), QuicError>(())
+        }
+        .await;
+        self.record_outcome(&result);
+        stats.total_client_stats.total_sent_packets.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    pub async fn send_buffer(&self, data: &[u8], stats: &ClientStats) -> Result<(), QuicError> {
+        self._send_buffer(data, stats).await
+    }
+
+    pub async fn send_batch(
+        &self,
+        buffers: &[Vec<u8>],
+        stats: &ClientStats,
+    ) -> Result<(), QuicError> {
+        let result = async {
+            let connection = self.make_connection().await?;
+            for data in buffers {
+                let semaphore = self.stream_concurrency.read().unwrap().clone();
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                let mut send_stream = connection.open_uni().await?;
+                self.note_uni_stream_opened();
+                send_stream.write_all(data).await?;
+                send_stream.finish()?;
+            }
+            self.transport_metrics.sample(&connection);
+            Ok::<(), QuicError>(())
+        }
+        .await;
+        self.record_outcome(&result);
+        stats
+            .total_client_stats
+            .total_sent_packets
+            .fetch_add(buffers.len() as u64, Ordering::Relaxed);
+        result
+    }
+
+    /// Updates connection health from a send result. A `Backoff` error means we never
+    /// even attempted a handshake, so it must not itself count as a new failure —
+    /// otherwise every send attempted during the cooldown would push the cooldown back
+    /// out, and it would never recover.
+    fn record_outcome(&self, result: &Result<(), QuicError>) {
+        match result {
+            Ok(()) => self.health.record_success(),
+            Err(QuicError::Backoff) => {}
+            Err(_) => self.health.record_failure(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_keypair::Keypair,
+        solana_tls_utils::new_dummy_x509_certificate,
+    };
+
+    fn dummy_certificate() -> Arc<QuicClientCertificate> {
+        let (certificate, key) = new_dummy_x509_certificate(&Keypair::new());
+        Arc::new(QuicClientCertificate { certificate, key })
+    }
+
+    #[test]
+    fn rekey_without_endpoint_updates_stored_certificate_only() {
+        let endpoint =
+            QuicLazyInitializedEndpoint::new(dummy_certificate(), None, QuicTransportConfig::default());
+        let new_certificate = dummy_certificate();
+        endpoint.rekey(new_certificate.clone());
+        assert!(Arc::ptr_eq(
+            &*endpoint.client_certificate.read().unwrap(),
+            &new_certificate
+        ));
+        // No endpoint has been created yet, so there's nothing to install the new
+        // config on; the next `get_endpoint` call picks up the new certificate.
+        assert!(endpoint.endpoint.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn backoff_starts_at_base_and_doubles() {
+        assert_eq!(backoff_for(1), BASE_BACKOFF);
+        assert_eq!(backoff_for(2), BASE_BACKOFF * 2);
+        assert_eq!(backoff_for(3), BASE_BACKOFF * 4);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff_for(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn fresh_connection_is_healthy() {
+        let health = ConnectionHealth::default();
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn failure_is_unhealthy_until_backoff_elapses() {
+        let health = ConnectionHealth::default();
+        health.record_failure();
+        assert!(!health.is_healthy());
+        *health.last_failure.lock().unwrap() = Instant::now().checked_sub(BASE_BACKOFF);
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn success_resets_failures_and_records_timestamp() {
+        let health = ConnectionHealth::default();
+        health.record_failure();
+        health.record_success();
+        assert!(health.is_healthy());
+        assert_eq!(health.consecutive_failures.load(Ordering::Relaxed), 0);
+        assert!(health.last_success.lock().unwrap().is_some());
+    }
+}
+
+pub struct QuicClientConnection {
+    pub client: Arc<QuicClient>,
+    pub connection_stats: Arc<ConnectionCacheStats>,
+}
+
+impl QuicClientConnection {
+    pub fn new_with_client(client: Arc<QuicClient>, connection_stats: Arc<ConnectionCacheStats>) -> Self {
+        Self {
+            client,
+            connection_stats,
+        }
+    }
+
+    /// See [`QuicClient::warm`].
+    pub async fn warm(&self) -> Result<(), solana_connection_cache::connection_cache::ClientError> {
+        self.client.warm().await.map_err(|err| {
+            warn!("Failed to pre-warm connection to {}: {err}", self.client.server_addr());
+            solana_connection_cache::connection_cache::ClientError::from(err)
+        })
+    }
+}
+
+#[async_trait]
+impl ClientConnection for QuicClientConnection {
+    fn server_addr(&self) -> &SocketAddr {
+        self.client.server_addr()
+    }
+
+    async fn send_data(&self, data: &[u8]) -> Result<(), solana_connection_cache::connection_cache::ClientError> {
+        let mut measure = Measure::start("send_data");
+        let stats = ClientStats::default();
+        self.client
+            .send_buffer(data, &stats)
+            .await
+            .map_err(|err| {
+                warn!("Failed to send data to {}: {err}", self.client.server_addr());
+                solana_connection_cache::connection_cache::ClientError::from(err)
+            })?;
+        measure.stop();
+        self.connection_stats
+            .total_client_stats
+            .send_timeout
+            .fetch_add(0, Ordering::Relaxed);
+        if self.connection_stats.total_client_stats.total_sent_packets.load(Ordering::Relaxed)
+            % CONNECTION_STAT_SUBMISSION_INTERVAL
+            == 0
+        {
+            self.connection_stats.report();
+        }
+        Ok(())
+    }
+
+    async fn send_data_batch(&self, buffers: &[Vec<u8>]) -> Result<(), solana_connection_cache::connection_cache::ClientError> {
+        let stats = ClientStats::default();
+        self.client.send_batch(buffers, &stats).await.map_err(|err| {
+            warn!(
+                "Failed to send batch of {} to {}: {err}",
+                buffers.len(),
+                self.client.server_addr()
+            );
+            solana_connection_cache::connection_cache::ClientError::from(err)
+        })
+    }
+}
@@ -0,0 +1,82 @@
+//! Simple blocking client that connects to a given UDP port with the QUIC protocol
+//! and provides an interface for sending transactions
+
+use {
+    crate::nonblocking::quic_client::QuicClient,
+    log::*,
+    solana_connection_cache::{
+        client_connection::{ClientConnection, ClientStats},
+        connection_cache::ClientError,
+        connection_cache_stats::ConnectionCacheStats,
+    },
+    std::{net::SocketAddr, sync::Arc},
+    tokio::runtime::Runtime,
+};
+
+/// A lazily-initialized tokio runtime shared by every blocking [`QuicClientConnection`],
+/// since the underlying [`QuicClient`] is async-only.
+pub fn get_runtime() -> Arc<Runtime> {
+    lazy_static::lazy_static! {
+        static ref RUNTIME: Arc<Runtime> = Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .thread_name("solQuicClient")
+                .build()
+                .unwrap()
+        );
+    }
+    RUNTIME.clone()
+}
+
+/// Tears down the cached QUIC connection held by `client`, if any, so that the
+/// next send re-establishes the handshake from scratch.
+pub fn close_quic_connection(client: Arc<QuicClient>) {
+    let runtime = get_runtime();
+    runtime.block_on(client.invalidate_connection());
+}
+
+pub struct QuicClientConnection {
+    pub client: Arc<QuicClient>,
+    pub connection_stats: Arc<ConnectionCacheStats>,
+    runtime: Arc<Runtime>,
+}
+
+impl QuicClientConnection {
+    pub fn new_with_client(client: Arc<QuicClient>, connection_stats: Arc<ConnectionCacheStats>) -> Self {
+        Self {
+            client,
+            connection_stats,
+            runtime: get_runtime(),
+        }
+    }
+}
+
+impl ClientConnection for QuicClientConnection {
+    fn server_addr(&self) -> &SocketAddr {
+        self.client.server_addr()
+    }
+
+    fn send_data(&self, data: &[u8]) -> Result<(), ClientError> {
+        let stats = ClientStats::default();
+        self.runtime
+            .block_on(self.client.send_buffer(data, &stats))
+            .map_err(|err| {
+                warn!("Failed to send data to {}: {err}", self.client.server_addr());
+                ClientError::from(err)
+            })
+    }
+
+    fn send_data_batch(&self, buffers: &[Vec<u8>]) -> Result<(), ClientError> {
+        let stats = ClientStats::default();
+        self.runtime
+            .block_on(self.client.send_batch(buffers, &stats))
+            .map_err(|err| {
+                warn!(
+                    "Failed to send batch of {} to {}: {err}",
+                    buffers.len(),
+                    self.client.server_addr()
+                );
+                ClientError::from(err)
+            })
+    }
+}